@@ -0,0 +1,230 @@
+//! Storage Commitment Push Model SOP class (1.2.840.10008.1.20.1).
+//!
+//! Modalities that push images via C-STORE typically follow up with an
+//! N-ACTION-RQ asking the archive to confirm it has safely committed a set
+//! of SOP instances before the modality deletes its local copies. We answer
+//! the N-ACTION-RQ immediately, then report the outcome via
+//! N-EVENT-REPORT-RQ - either on the same association, or, if that fails,
+//! from a short-lived outbound association queued for later delivery.
+
+use crate::dimse::{self, tag};
+use anyhow::{anyhow, Context, Result};
+use dicom_core::header::DataElement;
+use dicom_core::value::Value;
+use dicom_core::{PrimitiveValue, Tag, VR};
+use dicom_object::InMemDicomObject;
+use std::net::SocketAddr;
+
+/// Storage Commitment Push Model SOP Class UID.
+pub const SOP_CLASS_UID: &str = "1.2.840.10008.1.20.1";
+/// Well-known SOP Instance UID used for this SOP class's N-ACTION/N-EVENT-REPORT.
+pub const SOP_INSTANCE_UID: &str = "1.2.840.10008.1.20.1.1";
+
+const ACTION_TYPE_STORAGE_COMMITMENT_REQUEST: u16 = 1;
+
+const TRANSACTION_UID: Tag = Tag(0x0008, 0x1195);
+const REFERENCED_SOP_SEQUENCE: Tag = Tag(0x0008, 0x1199);
+const FAILED_SOP_SEQUENCE: Tag = Tag(0x0008, 0x1198);
+const REFERENCED_SOP_CLASS_UID: Tag = Tag(0x0008, 0x1150);
+const REFERENCED_SOP_INSTANCE_UID: Tag = Tag(0x0008, 0x1155);
+const FAILURE_REASON: Tag = Tag(0x0008, 0x1197);
+
+/// "No such SOP Instance" (PS3.3 Annex C.13, storage commitment failure reasons).
+const FAILURE_NO_SUCH_OBJECT: u16 = 0x0112;
+
+#[derive(Debug, Clone)]
+pub struct ReferencedSop {
+    pub sop_class_uid: String,
+    pub sop_instance_uid: String,
+}
+
+/// A storage commitment request that could not be reported on its original
+/// association, queued for delivery from a fresh outbound association.
+#[derive(Debug, Clone)]
+pub struct PendingCommitment {
+    pub peer_addr: SocketAddr,
+    pub calling_ae_title: String,
+    pub transaction_uid: String,
+    pub committed: Vec<ReferencedSop>,
+    pub failed: Vec<ReferencedSop>,
+    pub attempts: u32,
+}
+
+/// ActionTypeID must be 1 ("Request Storage Commitment") for this SOP class.
+/// ActionTypeID (0000,1008) is read from the N-ACTION-RQ command set.
+pub fn is_commitment_request(command: &InMemDicomObject) -> bool {
+    dimse::get_u16(command, tag::ACTION_TYPE_ID)
+        .map(|v| v == ACTION_TYPE_STORAGE_COMMITMENT_REQUEST)
+        .unwrap_or(false)
+}
+
+pub fn transaction_uid(identifier: &InMemDicomObject) -> Result<String> {
+    dimse::get_str(identifier, TRANSACTION_UID)
+}
+
+/// Parse the ReferencedSOPSequence (0008,1199) of an N-ACTION-RQ identifier.
+pub fn parse_referenced_sops(identifier: &InMemDicomObject) -> Result<Vec<ReferencedSop>> {
+    let elem = identifier
+        .element(REFERENCED_SOP_SEQUENCE)
+        .context("N-ACTION-RQ identifier is missing ReferencedSOPSequence (0008,1199)")?;
+    let items = elem
+        .items()
+        .ok_or_else(|| anyhow!("ReferencedSOPSequence (0008,1199) is not a sequence"))?;
+
+    items
+        .iter()
+        .map(|item| {
+            Ok(ReferencedSop {
+                sop_class_uid: dimse::get_str(item, REFERENCED_SOP_CLASS_UID)?,
+                sop_instance_uid: dimse::get_str(item, REFERENCED_SOP_INSTANCE_UID)?,
+            })
+        })
+        .collect()
+}
+
+fn referenced_sop_item(sop: &ReferencedSop) -> InMemDicomObject {
+    InMemDicomObject::from_element_iter(vec![
+        DataElement::new(
+            REFERENCED_SOP_CLASS_UID,
+            VR::UI,
+            PrimitiveValue::from(sop.sop_class_uid.as_str()),
+        ),
+        DataElement::new(
+            REFERENCED_SOP_INSTANCE_UID,
+            VR::UI,
+            PrimitiveValue::from(sop.sop_instance_uid.as_str()),
+        ),
+    ])
+}
+
+fn failed_sop_item(sop: &ReferencedSop) -> InMemDicomObject {
+    InMemDicomObject::from_element_iter(vec![
+        DataElement::new(
+            REFERENCED_SOP_CLASS_UID,
+            VR::UI,
+            PrimitiveValue::from(sop.sop_class_uid.as_str()),
+        ),
+        DataElement::new(
+            REFERENCED_SOP_INSTANCE_UID,
+            VR::UI,
+            PrimitiveValue::from(sop.sop_instance_uid.as_str()),
+        ),
+        DataElement::new(
+            FAILURE_REASON,
+            VR::US,
+            PrimitiveValue::from(FAILURE_NO_SUCH_OBJECT),
+        ),
+    ])
+}
+
+/// EventTypeID: 1 = all referenced instances committed, 2 = partial success.
+pub fn event_type_id(failed: &[ReferencedSop]) -> u16 {
+    if failed.is_empty() {
+        1
+    } else {
+        2
+    }
+}
+
+/// Build the N-EVENT-REPORT-RQ identifier: the transaction UID being
+/// reported on, plus the committed and failed instance sequences.
+pub fn build_event_report_identifier(
+    transaction_uid: &str,
+    committed: &[ReferencedSop],
+    failed: &[ReferencedSop],
+) -> InMemDicomObject {
+    let mut identifier = InMemDicomObject::new_empty();
+    identifier.put(DataElement::new(
+        TRANSACTION_UID,
+        VR::UI,
+        PrimitiveValue::from(transaction_uid),
+    ));
+
+    if !committed.is_empty() {
+        let items: Vec<InMemDicomObject> = committed.iter().map(referenced_sop_item).collect();
+        identifier.put(DataElement::new(
+            REFERENCED_SOP_SEQUENCE,
+            VR::SQ,
+            Value::Sequence(items.into()),
+        ));
+    }
+    if !failed.is_empty() {
+        let items: Vec<InMemDicomObject> = failed.iter().map(failed_sop_item).collect();
+        identifier.put(DataElement::new(
+            FAILED_SOP_SEQUENCE,
+            VR::SQ,
+            Value::Sequence(items.into()),
+        ));
+    }
+
+    identifier
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sop(class: &str, instance: &str) -> ReferencedSop {
+        ReferencedSop {
+            sop_class_uid: class.to_string(),
+            sop_instance_uid: instance.to_string(),
+        }
+    }
+
+    #[test]
+    fn event_type_id_all_committed_is_one() {
+        assert_eq!(event_type_id(&[]), 1);
+    }
+
+    #[test]
+    fn event_type_id_with_failures_is_two() {
+        let failed = vec![sop("1.2.840.10008.5.1.4.1.1.7", "1.2.3.4")];
+        assert_eq!(event_type_id(&failed), 2);
+    }
+
+    #[test]
+    fn referenced_sops_round_trip_through_the_identifier() {
+        let committed = vec![sop("1.2.840.10008.5.1.4.1.1.7", "1.2.3.4.1")];
+        let failed = vec![sop("1.2.840.10008.5.1.4.1.1.7", "1.2.3.4.2")];
+
+        let identifier = build_event_report_identifier("1.2.3.uid", &committed, &failed);
+
+        assert_eq!(
+            dimse::get_str(&identifier, TRANSACTION_UID).unwrap(),
+            "1.2.3.uid"
+        );
+
+        let committed_items = identifier.element(REFERENCED_SOP_SEQUENCE).unwrap().items().unwrap();
+        assert_eq!(committed_items.len(), 1);
+        assert_eq!(
+            dimse::get_str(&committed_items[0], REFERENCED_SOP_INSTANCE_UID).unwrap(),
+            "1.2.3.4.1"
+        );
+
+        let failed_items = identifier.element(FAILED_SOP_SEQUENCE).unwrap().items().unwrap();
+        assert_eq!(failed_items.len(), 1);
+        assert_eq!(
+            dimse::get_str(&failed_items[0], REFERENCED_SOP_INSTANCE_UID).unwrap(),
+            "1.2.3.4.2"
+        );
+    }
+
+    #[test]
+    fn parse_referenced_sops_reads_back_items_built_for_a_request() {
+        let items = vec![
+            referenced_sop_item(&sop("1.2.840.10008.5.1.4.1.1.7", "1.2.3.4.1")),
+            referenced_sop_item(&sop("1.2.840.10008.5.1.4.1.1.7", "1.2.3.4.2")),
+        ];
+        let mut identifier = InMemDicomObject::new_empty();
+        identifier.put(DataElement::new(
+            REFERENCED_SOP_SEQUENCE,
+            VR::SQ,
+            Value::Sequence(items.into()),
+        ));
+
+        let parsed = parse_referenced_sops(&identifier).unwrap();
+        assert_eq!(parsed.len(), 2);
+        assert_eq!(parsed[0].sop_instance_uid, "1.2.3.4.1");
+        assert_eq!(parsed[1].sop_instance_uid, "1.2.3.4.2");
+    }
+}
@@ -15,6 +15,19 @@ pub struct ServerConfig {
     pub port: u16,
     pub ae_title: String,
     pub max_pdu_length: u32,
+    /// Bind address for the DICOMweb (QIDO-RS/WADO-RS/STOW-RS) HTTP server.
+    #[serde(default = "default_http_host")]
+    pub http_host: String,
+    #[serde(default = "default_http_port")]
+    pub http_port: u16,
+}
+
+fn default_http_host() -> String {
+    "0.0.0.0".to_string()
+}
+
+fn default_http_port() -> u16 {
+    8080
 }
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
@@ -25,9 +38,37 @@ pub struct DatabaseConfig {
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct StorageConfig {
+    #[serde(default)]
+    pub backend: StorageBackendKind,
     pub base_path: String,
     pub organize_by_patient: bool,
     pub organize_by_study: bool,
+    /// S3-compatible endpoint URL (e.g. a MinIO or Garage deployment).
+    /// Ignored when `backend` is `Filesystem`; `None` uses the default AWS
+    /// endpoint for the configured region.
+    pub endpoint: Option<String>,
+    pub bucket: Option<String>,
+    pub region: Option<String>,
+    pub access_key_id: Option<String>,
+    pub secret_access_key: Option<String>,
+    /// Transfer syntax to normalize incoming objects to before storing them
+    /// (e.g. Explicit VR Little Endian). `None` stores objects as received,
+    /// with no transcoding.
+    pub store_transfer_syntax: Option<String>,
+}
+
+/// Which `StorageBackend` implementation to construct.
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum StorageBackendKind {
+    Filesystem,
+    S3,
+}
+
+impl Default for StorageBackendKind {
+    fn default() -> Self {
+        StorageBackendKind::Filesystem
+    }
 }
 
 impl Config {
@@ -44,6 +85,8 @@ impl Config {
                 port: 11112,
                 ae_title: "RUST_SCP".to_string(),
                 max_pdu_length: 16384,
+                http_host: "0.0.0.0".to_string(),
+                http_port: 8080,
             },
             database: DatabaseConfig {
                 url: std::env::var("DATABASE_URL")
@@ -51,10 +94,17 @@ impl Config {
                 max_connections: 10,
             },
             storage: StorageConfig {
+                backend: StorageBackendKind::Filesystem,
                 base_path: std::env::var("DICOM_STORAGE_PATH")
                     .unwrap_or_else(|_| "/var/pacs/storage".to_string()),
                 organize_by_patient: true,
                 organize_by_study: true,
+                endpoint: None,
+                bucket: None,
+                region: None,
+                access_key_id: None,
+                secret_access_key: None,
+                store_transfer_syntax: None,
             },
         }
     }
@@ -1,4 +1,5 @@
-use sqlx::{PgPool, Row};
+use crate::query::{FindQuery, InstanceRow, PatientRow, SeriesRow, StudyRow};
+use sqlx::{PgPool, QueryBuilder, Row};
 use uuid::Uuid;
 use anyhow::Result;
 use dicom_object::InMemDicomObject;
@@ -254,6 +255,216 @@ impl Database {
 
         Ok(())
     }
+
+    /// Study-level C-FIND matching against `worklist_patient` and
+    /// `worklist_study`, honoring the matching keys present in `query`.
+    pub async fn find_studies(&self, query: &FindQuery) -> Result<Vec<StudyRow>> {
+        let mut builder = QueryBuilder::new(
+            r#"
+            SELECT p.patient_id, p.patient_name, p.patient_birth_date, p.patient_sex,
+                   s.study_instance_uid, s.study_date, s.study_time,
+                   s.accession_number, s.study_description, s.modality
+            FROM worklist_study s
+            JOIN worklist_patient p ON p.id = s.patient_id
+            WHERE 1 = 1
+            "#,
+        );
+
+        if let Some(patient_id) = &query.patient_id {
+            builder
+                .push(" AND p.patient_id LIKE ")
+                .push_bind(crate::query::to_sql_like(patient_id));
+        }
+        if let Some(patient_name) = &query.patient_name {
+            builder
+                .push(" AND p.patient_name LIKE ")
+                .push_bind(crate::query::to_sql_like(patient_name));
+        }
+        if let Some(study_uid) = &query.study_instance_uid {
+            builder
+                .push(" AND s.study_instance_uid = ")
+                .push_bind(study_uid.clone());
+        }
+        if let Some(accession) = &query.accession_number {
+            builder
+                .push(" AND s.accession_number = ")
+                .push_bind(accession.clone());
+        }
+        if let Some(modality) = &query.modality {
+            builder.push(" AND s.modality = ").push_bind(modality.clone());
+        }
+        if let Some(range) = &query.study_date {
+            if let Some(from) = &range.from {
+                builder.push(" AND s.study_date >= ").push_bind(from.clone());
+            }
+            if let Some(to) = &range.to {
+                builder.push(" AND s.study_date <= ").push_bind(to.clone());
+            }
+        }
+
+        let rows = builder.build().fetch_all(&self.pool).await?;
+        Ok(rows
+            .iter()
+            .map(|row| StudyRow {
+                patient_id: row.get("patient_id"),
+                patient_name: row.get("patient_name"),
+                patient_birth_date: row.get("patient_birth_date"),
+                patient_sex: row.get("patient_sex"),
+                study_instance_uid: row.get("study_instance_uid"),
+                study_date: row.get("study_date"),
+                study_time: row.get("study_time"),
+                accession_number: row.get("accession_number"),
+                study_description: row.get("study_description"),
+                modality: row.get("modality"),
+            })
+            .collect())
+    }
+
+    /// Patient-level C-FIND matching against `worklist_patient` alone, so a
+    /// patient with several studies is reported exactly once.
+    pub async fn find_patients(&self, query: &FindQuery) -> Result<Vec<PatientRow>> {
+        let mut builder = QueryBuilder::new(
+            r#"
+            SELECT p.patient_id, p.patient_name, p.patient_birth_date, p.patient_sex
+            FROM worklist_patient p
+            WHERE 1 = 1
+            "#,
+        );
+
+        if let Some(patient_id) = &query.patient_id {
+            builder
+                .push(" AND p.patient_id LIKE ")
+                .push_bind(crate::query::to_sql_like(patient_id));
+        }
+        if let Some(patient_name) = &query.patient_name {
+            builder
+                .push(" AND p.patient_name LIKE ")
+                .push_bind(crate::query::to_sql_like(patient_name));
+        }
+
+        let rows = builder.build().fetch_all(&self.pool).await?;
+        Ok(rows
+            .iter()
+            .map(|row| PatientRow {
+                patient_id: row.get("patient_id"),
+                patient_name: row.get("patient_name"),
+                patient_birth_date: row.get("patient_birth_date"),
+                patient_sex: row.get("patient_sex"),
+            })
+            .collect())
+    }
+
+    /// Series-level C-FIND matching against `worklist_series`, scoped to a
+    /// study and optionally filtered by modality.
+    pub async fn find_series(&self, query: &FindQuery) -> Result<Vec<SeriesRow>> {
+        let mut builder = QueryBuilder::new(
+            r#"
+            SELECT st.study_instance_uid, se.series_instance_uid,
+                   se.series_number, se.series_description, se.modality
+            FROM worklist_series se
+            JOIN worklist_study st ON st.id = se.study_id
+            WHERE 1 = 1
+            "#,
+        );
+
+        if let Some(study_uid) = &query.study_instance_uid {
+            builder
+                .push(" AND st.study_instance_uid = ")
+                .push_bind(study_uid.clone());
+        }
+        if let Some(series_uid) = &query.series_instance_uid {
+            builder
+                .push(" AND se.series_instance_uid = ")
+                .push_bind(series_uid.clone());
+        }
+        if let Some(modality) = &query.modality {
+            builder.push(" AND se.modality = ").push_bind(modality.clone());
+        }
+
+        let rows = builder.build().fetch_all(&self.pool).await?;
+        Ok(rows
+            .iter()
+            .map(|row| SeriesRow {
+                study_instance_uid: row.get("study_instance_uid"),
+                series_instance_uid: row.get("series_instance_uid"),
+                series_number: row.get("series_number"),
+                series_description: row.get("series_description"),
+                modality: row.get("modality"),
+            })
+            .collect())
+    }
+
+    /// Instance-level listing for QIDO-RS `/series/{uid}/instances`.
+    pub async fn find_instances(&self, series_instance_uid: &str) -> Result<Vec<InstanceRow>> {
+        let rows = sqlx::query(
+            r#"
+            SELECT sop_instance_uid, sop_class_uid, instance_number,
+                   dicom_file, transfer_syntax_uid
+            FROM worklist_dicomimage
+            WHERE series_id = (
+                SELECT id FROM worklist_series WHERE series_instance_uid = $1
+            )
+            "#,
+        )
+        .bind(series_instance_uid)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows
+            .iter()
+            .map(|row| InstanceRow {
+                sop_instance_uid: row.get("sop_instance_uid"),
+                sop_class_uid: row.get("sop_class_uid"),
+                instance_number: row.get("instance_number"),
+                dicom_file: row.get("dicom_file"),
+                transfer_syntax_uid: row.get("transfer_syntax_uid"),
+            })
+            .collect())
+    }
+
+    /// Stored location and transfer syntax of an instance, scoped to the
+    /// study and series it must belong to (WADO-RS's hierarchical retrieval
+    /// contract: `/studies/{uid}/series/{s}/instances/{i}` must 404 if `i`
+    /// isn't actually part of `{uid}/{s}`, even if it exists elsewhere).
+    pub async fn get_instance_location(
+        &self,
+        study_instance_uid: &str,
+        series_instance_uid: &str,
+        sop_instance_uid: &str,
+    ) -> Result<Option<(String, String)>> {
+        let row = sqlx::query(
+            r#"
+            SELECT i.dicom_file, i.transfer_syntax_uid
+            FROM worklist_dicomimage i
+            JOIN worklist_series se ON se.id = i.series_id
+            JOIN worklist_study st ON st.id = se.study_id
+            WHERE st.study_instance_uid = $1
+              AND se.series_instance_uid = $2
+              AND i.sop_instance_uid = $3
+            LIMIT 1
+            "#,
+        )
+        .bind(study_instance_uid)
+        .bind(series_instance_uid)
+        .bind(sop_instance_uid)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(row.map(|row| (row.get("dicom_file"), row.get("transfer_syntax_uid"))))
+    }
+
+    /// Whether an instance is already known to the archive, used by Storage
+    /// Commitment to verify referenced SOP instances before committing.
+    pub async fn instance_exists(&self, sop_instance_uid: &str) -> Result<bool> {
+        let row = sqlx::query(
+            "SELECT 1 AS present FROM worklist_dicomimage WHERE sop_instance_uid = $1 LIMIT 1",
+        )
+        .bind(sop_instance_uid)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(row.is_some())
+    }
 }
 
 fn get_string_value(obj: &InMemDicomObject, tag_name: &str) -> Option<String> {
@@ -0,0 +1,482 @@
+//! Minimal DIMSE command/data-set assembly on top of the raw P-DATA-TF PDUs.
+//!
+//! The upper layer (`scp.rs`) hands us PDV fragments as they arrive off the
+//! wire; we buffer them per presentation context until the "last fragment"
+//! bit is set, then decode the resulting command set (always Implicit VR
+//! Little Endian, per the DICOM standard) and, where applicable, the data
+//! set that follows it in the transfer syntax negotiated for that context.
+
+use anyhow::{anyhow, Context, Result};
+use dicom_core::header::{DataElement, Tag};
+use dicom_core::{PrimitiveValue, VR};
+use dicom_object::InMemDicomObject;
+use dicom_transfer_syntax_registry::TransferSyntaxRegistry;
+use dicom_ul::pdu::{PDataValue, PDataValueType};
+
+/// Implicit VR Little Endian - the transfer syntax every DIMSE command set
+/// is encoded with, regardless of the transfer syntax negotiated for data.
+pub const IMPLICIT_VR_LE: &str = "1.2.840.10008.1.2";
+
+/// Well-known DIMSE command fields (PS3.7 Table 9.3-1 and friends).
+pub mod command_field {
+    pub const C_STORE_RQ: u16 = 0x0001;
+    pub const C_STORE_RSP: u16 = 0x8001;
+    pub const C_FIND_RQ: u16 = 0x0020;
+    pub const C_FIND_RSP: u16 = 0x8020;
+    pub const C_CANCEL_RQ: u16 = 0x0FFF;
+    pub const N_ACTION_RQ: u16 = 0x0130;
+    pub const N_ACTION_RSP: u16 = 0x8130;
+    pub const N_EVENT_REPORT_RQ: u16 = 0x0100;
+    pub const N_EVENT_REPORT_RSP: u16 = 0x8100;
+}
+
+/// DIMSE status codes we actually emit.
+pub mod status {
+    pub const SUCCESS: u16 = 0x0000;
+    pub const PENDING: u16 = 0xFF00;
+    pub const OUT_OF_RESOURCES: u16 = 0xA700;
+    pub const CANNOT_UNDERSTAND: u16 = 0xC000;
+}
+
+/// Command set tags used when reading requests and building responses.
+pub mod tag {
+    use dicom_core::Tag;
+
+    pub const AFFECTED_SOP_CLASS_UID: Tag = Tag(0x0000, 0x0002);
+    pub const COMMAND_FIELD: Tag = Tag(0x0000, 0x0100);
+    pub const MESSAGE_ID: Tag = Tag(0x0000, 0x0110);
+    pub const MESSAGE_ID_BEING_RESPONDED_TO: Tag = Tag(0x0000, 0x0120);
+    pub const COMMAND_DATA_SET_TYPE: Tag = Tag(0x0000, 0x0800);
+    pub const STATUS: Tag = Tag(0x0000, 0x0900);
+    pub const AFFECTED_SOP_INSTANCE_UID: Tag = Tag(0x0000, 0x1000);
+    pub const ACTION_TYPE_ID: Tag = Tag(0x0000, 0x1008);
+    pub const EVENT_TYPE_ID: Tag = Tag(0x0000, 0x1002);
+}
+
+/// No data set accompanies this command (PS3.7 Table 9.1-1).
+const NO_DATA_SET: u16 = 0x0101;
+
+/// Accumulates P-DATA-TF fragments for one presentation context until the
+/// command set (and, if present, the following data set) is complete.
+///
+/// A single `FragmentAssembler` is meant to live for the duration of one
+/// DIMSE message; callers drop or reset it after consuming a complete message.
+#[derive(Default)]
+pub struct FragmentAssembler {
+    command: Vec<u8>,
+    command_done: bool,
+    data: Vec<u8>,
+    data_done: bool,
+}
+
+impl FragmentAssembler {
+    pub fn push(&mut self, pdv: &PDataValue) {
+        match pdv.value_type {
+            PDataValueType::Command => {
+                self.command.extend_from_slice(&pdv.data);
+                self.command_done = pdv.is_last;
+            }
+            PDataValueType::Data => {
+                self.data.extend_from_slice(&pdv.data);
+                self.data_done = pdv.is_last;
+            }
+        }
+    }
+
+    /// Whether a full command set has been accumulated.
+    pub fn command_ready(&self) -> bool {
+        self.command_done && !self.command.is_empty()
+    }
+
+    /// Whether a full data set has been accumulated (only meaningful once
+    /// the caller knows, from the command set, that one is expected).
+    pub fn data_ready(&self) -> bool {
+        self.data_done && !self.data.is_empty()
+    }
+
+    /// Peek at the accumulated command bytes without consuming them, so the
+    /// caller can inspect CommandDataSetType before deciding whether to keep
+    /// buffering for a data set.
+    pub fn command_bytes(&self) -> &[u8] {
+        &self.command
+    }
+
+    /// Peek at the accumulated data-set bytes without consuming them.
+    pub fn data_bytes(&self) -> &[u8] {
+        &self.data
+    }
+
+    pub fn reset(&mut self) {
+        *self = Self::default();
+    }
+}
+
+/// Parse a complete command set buffer (always Implicit VR Little Endian).
+pub fn parse_command_set(bytes: &[u8]) -> Result<InMemDicomObject> {
+    let ts = TransferSyntaxRegistry
+        .get(IMPLICIT_VR_LE)
+        .expect("Implicit VR Little Endian is always registered");
+    InMemDicomObject::read_dataset_with_ts(bytes, ts).context("failed to parse DIMSE command set")
+}
+
+/// Parse a complete data set buffer using the transfer syntax negotiated for
+/// the presentation context it arrived on.
+pub fn parse_data_set(bytes: &[u8], transfer_syntax_uid: &str) -> Result<InMemDicomObject> {
+    let ts = TransferSyntaxRegistry
+        .get(transfer_syntax_uid)
+        .ok_or_else(|| anyhow!("unknown transfer syntax: {transfer_syntax_uid}"))?;
+    InMemDicomObject::read_dataset_with_ts(bytes, ts).context("failed to parse data set")
+}
+
+pub fn command_field_of(cmd: &InMemDicomObject) -> Result<u16> {
+    get_u16(cmd, tag::COMMAND_FIELD).context("command set is missing CommandField (0000,0100)")
+}
+
+/// Whether the command set declares a following data set
+/// (CommandDataSetType != 0x0101).
+pub fn has_data_set(cmd: &InMemDicomObject) -> bool {
+    get_u16(cmd, tag::COMMAND_DATA_SET_TYPE)
+        .map(|v| v != NO_DATA_SET)
+        .unwrap_or(true)
+}
+
+pub fn get_u16(obj: &InMemDicomObject, t: Tag) -> Result<u16> {
+    obj.element(t)?
+        .to_int::<u16>()
+        .map_err(|e| anyhow!("failed to read tag {}: {}", t, e))
+}
+
+pub fn get_str(obj: &InMemDicomObject, t: Tag) -> Result<String> {
+    obj.element(t)?
+        .to_str()
+        .map(|s| s.trim_end_matches('\0').to_string())
+        .map_err(|e| anyhow!("failed to read tag {}: {}", t, e))
+}
+
+/// Build a C-STORE-RSP command set echoing the request's identifiers.
+pub fn build_cstore_rsp(rq: &InMemDicomObject, status_code: u16) -> Result<InMemDicomObject> {
+    let message_id = get_u16(rq, tag::MESSAGE_ID)?;
+    let affected_sop_class = get_str(rq, tag::AFFECTED_SOP_CLASS_UID)?;
+    let affected_sop_instance = get_str(rq, tag::AFFECTED_SOP_INSTANCE_UID)?;
+
+    let mut rsp = InMemDicomObject::new_empty();
+    rsp.put(DataElement::new(
+        tag::AFFECTED_SOP_CLASS_UID,
+        VR::UI,
+        PrimitiveValue::from(affected_sop_class),
+    ));
+    rsp.put(DataElement::new(
+        tag::COMMAND_FIELD,
+        VR::US,
+        PrimitiveValue::from(command_field::C_STORE_RSP),
+    ));
+    rsp.put(DataElement::new(
+        tag::MESSAGE_ID_BEING_RESPONDED_TO,
+        VR::US,
+        PrimitiveValue::from(message_id),
+    ));
+    rsp.put(DataElement::new(
+        tag::COMMAND_DATA_SET_TYPE,
+        VR::US,
+        PrimitiveValue::from(NO_DATA_SET),
+    ));
+    rsp.put(DataElement::new(
+        tag::STATUS,
+        VR::US,
+        PrimitiveValue::from(status_code),
+    ));
+    rsp.put(DataElement::new(
+        tag::AFFECTED_SOP_INSTANCE_UID,
+        VR::UI,
+        PrimitiveValue::from(affected_sop_instance),
+    ));
+    Ok(rsp)
+}
+
+/// Build a C-FIND-RSP command set. `status_code` is `status::PENDING` for
+/// each match (carrying a following identifier) or `status::SUCCESS` for
+/// the final response (no identifier).
+pub fn build_cfind_rsp(rq: &InMemDicomObject, status_code: u16) -> Result<InMemDicomObject> {
+    let message_id = get_u16(rq, tag::MESSAGE_ID)?;
+    let affected_sop_class = get_str(rq, tag::AFFECTED_SOP_CLASS_UID)?;
+    let has_identifier = status_code == status::PENDING;
+
+    let mut rsp = InMemDicomObject::new_empty();
+    rsp.put(DataElement::new(
+        tag::AFFECTED_SOP_CLASS_UID,
+        VR::UI,
+        PrimitiveValue::from(affected_sop_class),
+    ));
+    rsp.put(DataElement::new(
+        tag::COMMAND_FIELD,
+        VR::US,
+        PrimitiveValue::from(command_field::C_FIND_RSP),
+    ));
+    rsp.put(DataElement::new(
+        tag::MESSAGE_ID_BEING_RESPONDED_TO,
+        VR::US,
+        PrimitiveValue::from(message_id),
+    ));
+    rsp.put(DataElement::new(
+        tag::COMMAND_DATA_SET_TYPE,
+        VR::US,
+        PrimitiveValue::from(if has_identifier { 0x0001u16 } else { NO_DATA_SET }),
+    ));
+    rsp.put(DataElement::new(
+        tag::STATUS,
+        VR::US,
+        PrimitiveValue::from(status_code),
+    ));
+    Ok(rsp)
+}
+
+/// Encode a data set (e.g. a C-FIND-RSP identifier) in the given transfer
+/// syntax and split it into PDVs, marking the final one as last.
+pub fn encode_data_set(
+    obj: &InMemDicomObject,
+    transfer_syntax_uid: &str,
+    presentation_context_id: u8,
+    max_pdu_length: usize,
+) -> Result<Vec<PDataValue>> {
+    let ts = TransferSyntaxRegistry
+        .get(transfer_syntax_uid)
+        .ok_or_else(|| anyhow!("unknown transfer syntax: {transfer_syntax_uid}"))?;
+    let mut buf = Vec::new();
+    obj.write_dataset_with_ts(&mut buf, ts)
+        .context("failed to encode data set")?;
+    Ok(chunk_into_pdvs(
+        buf,
+        presentation_context_id,
+        PDataValueType::Data,
+        max_pdu_length,
+    ))
+}
+
+/// Build an N-ACTION-RSP command set echoing the request's identifiers.
+pub fn build_naction_rsp(rq: &InMemDicomObject, status_code: u16) -> Result<InMemDicomObject> {
+    let message_id = get_u16(rq, tag::MESSAGE_ID)?;
+    let affected_sop_class = get_str(rq, tag::AFFECTED_SOP_CLASS_UID)?;
+    let affected_sop_instance = get_str(rq, tag::AFFECTED_SOP_INSTANCE_UID)?;
+
+    let mut rsp = InMemDicomObject::new_empty();
+    rsp.put(DataElement::new(
+        tag::AFFECTED_SOP_CLASS_UID,
+        VR::UI,
+        PrimitiveValue::from(affected_sop_class),
+    ));
+    rsp.put(DataElement::new(
+        tag::COMMAND_FIELD,
+        VR::US,
+        PrimitiveValue::from(command_field::N_ACTION_RSP),
+    ));
+    rsp.put(DataElement::new(
+        tag::MESSAGE_ID_BEING_RESPONDED_TO,
+        VR::US,
+        PrimitiveValue::from(message_id),
+    ));
+    rsp.put(DataElement::new(
+        tag::COMMAND_DATA_SET_TYPE,
+        VR::US,
+        PrimitiveValue::from(NO_DATA_SET),
+    ));
+    rsp.put(DataElement::new(
+        tag::STATUS,
+        VR::US,
+        PrimitiveValue::from(status_code),
+    ));
+    rsp.put(DataElement::new(
+        tag::AFFECTED_SOP_INSTANCE_UID,
+        VR::UI,
+        PrimitiveValue::from(affected_sop_instance),
+    ));
+    Ok(rsp)
+}
+
+/// Build an N-EVENT-REPORT-RQ command set announcing a commitment outcome.
+/// Unlike the response builders above, this originates a new DIMSE message
+/// (we are acting as the event-report SCU), so it takes its own message ID.
+pub fn build_event_report_rq(
+    message_id: u16,
+    affected_sop_class_uid: &str,
+    affected_sop_instance_uid: &str,
+    event_type_id: u16,
+) -> Result<InMemDicomObject> {
+    let mut rq = InMemDicomObject::new_empty();
+    rq.put(DataElement::new(
+        tag::AFFECTED_SOP_CLASS_UID,
+        VR::UI,
+        PrimitiveValue::from(affected_sop_class_uid),
+    ));
+    rq.put(DataElement::new(
+        tag::COMMAND_FIELD,
+        VR::US,
+        PrimitiveValue::from(command_field::N_EVENT_REPORT_RQ),
+    ));
+    rq.put(DataElement::new(
+        tag::MESSAGE_ID,
+        VR::US,
+        PrimitiveValue::from(message_id),
+    ));
+    rq.put(DataElement::new(
+        tag::COMMAND_DATA_SET_TYPE,
+        VR::US,
+        PrimitiveValue::from(0x0001u16),
+    ));
+    rq.put(DataElement::new(
+        tag::AFFECTED_SOP_INSTANCE_UID,
+        VR::UI,
+        PrimitiveValue::from(affected_sop_instance_uid),
+    ));
+    rq.put(DataElement::new(
+        tag::EVENT_TYPE_ID,
+        VR::US,
+        PrimitiveValue::from(event_type_id),
+    ));
+    Ok(rq)
+}
+
+/// Encode a command set as Implicit VR Little Endian and split it into
+/// PDVs no larger than `max_pdu_length`, marking the final one as last.
+pub fn encode_command(
+    cmd: &InMemDicomObject,
+    presentation_context_id: u8,
+    max_pdu_length: usize,
+) -> Result<Vec<PDataValue>> {
+    let ts = TransferSyntaxRegistry
+        .get(IMPLICIT_VR_LE)
+        .expect("Implicit VR Little Endian is always registered");
+    let mut buf = Vec::new();
+    cmd.write_dataset_with_ts(&mut buf, ts)
+        .context("failed to encode DIMSE command set")?;
+    Ok(chunk_into_pdvs(
+        buf,
+        presentation_context_id,
+        PDataValueType::Command,
+        max_pdu_length,
+    ))
+}
+
+/// Overhead that must fit alongside a PDV's payload within the negotiated
+/// max PDU length: the P-DATA-TF PDU header (6 bytes) plus one PDV item's
+/// own length/context-ID/message-control-byte header (4 + 1 + 1 bytes).
+const PDU_HEADER_OVERHEAD: usize = 12;
+
+fn chunk_into_pdvs(
+    data: Vec<u8>,
+    presentation_context_id: u8,
+    value_type: PDataValueType,
+    max_pdu_length: usize,
+) -> Vec<PDataValue> {
+    let chunk_size = max_pdu_length.saturating_sub(PDU_HEADER_OVERHEAD).max(1);
+    if data.is_empty() {
+        return vec![PDataValue {
+            presentation_context_id,
+            value_type,
+            is_last: true,
+            data,
+        }];
+    }
+
+    let mut chunks: Vec<PDataValue> = data
+        .chunks(chunk_size)
+        .map(|chunk| PDataValue {
+            presentation_context_id,
+            value_type,
+            is_last: false,
+            data: chunk.to_vec(),
+        })
+        .collect();
+    if let Some(last) = chunks.last_mut() {
+        last.is_last = true;
+    }
+    chunks
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pdv(value_type: PDataValueType, is_last: bool, data: &[u8]) -> PDataValue {
+        PDataValue {
+            presentation_context_id: 1,
+            value_type,
+            is_last,
+            data: data.to_vec(),
+        }
+    }
+
+    #[test]
+    fn fragment_assembler_reassembles_multi_fragment_command_and_data() {
+        let mut asm = FragmentAssembler::default();
+
+        asm.push(&pdv(PDataValueType::Command, false, b"comm"));
+        assert!(!asm.command_ready());
+        asm.push(&pdv(PDataValueType::Command, true, b"and"));
+        assert!(asm.command_ready());
+        assert_eq!(asm.command_bytes(), b"command");
+
+        asm.push(&pdv(PDataValueType::Data, false, b"da"));
+        assert!(!asm.data_ready());
+        asm.push(&pdv(PDataValueType::Data, true, b"ta"));
+        assert!(asm.data_ready());
+        assert_eq!(asm.data_bytes(), b"data");
+    }
+
+    #[test]
+    fn fragment_assembler_handles_data_less_command() {
+        let mut asm = FragmentAssembler::default();
+
+        asm.push(&pdv(PDataValueType::Command, true, b"command"));
+        assert!(asm.command_ready());
+        assert!(!asm.data_ready());
+        assert!(asm.data_bytes().is_empty());
+    }
+
+    #[test]
+    fn fragment_assembler_reset_clears_state() {
+        let mut asm = FragmentAssembler::default();
+        asm.push(&pdv(PDataValueType::Command, true, b"command"));
+        asm.push(&pdv(PDataValueType::Data, true, b"data"));
+        asm.reset();
+
+        assert!(!asm.command_ready());
+        assert!(!asm.data_ready());
+        assert!(asm.command_bytes().is_empty());
+        assert!(asm.data_bytes().is_empty());
+    }
+
+    #[test]
+    fn chunk_into_pdvs_splits_and_marks_last_chunk() {
+        let data = vec![0u8; 10];
+        let chunks = chunk_into_pdvs(data, 1, PDataValueType::Data, 16);
+
+        assert_eq!(chunks.len(), 3);
+        assert_eq!(chunks[0].data.len(), 4);
+        assert_eq!(chunks[1].data.len(), 4);
+        assert_eq!(chunks[2].data.len(), 2);
+        assert!(!chunks[0].is_last);
+        assert!(!chunks[1].is_last);
+        assert!(chunks[2].is_last);
+    }
+
+    #[test]
+    fn chunk_into_pdvs_accounts_for_pdu_header_overhead() {
+        let data = vec![0u8; 5];
+        let chunks = chunk_into_pdvs(data, 1, PDataValueType::Data, 16384);
+
+        let total: usize = chunks.iter().map(|c| c.data.len()).sum();
+        assert_eq!(total, 5);
+        for chunk in &chunks {
+            assert!(chunk.data.len() <= 16384 - PDU_HEADER_OVERHEAD);
+        }
+    }
+
+    #[test]
+    fn chunk_into_pdvs_empty_data_yields_single_last_pdv() {
+        let chunks = chunk_into_pdvs(Vec::new(), 1, PDataValueType::Command, 16384);
+
+        assert_eq!(chunks.len(), 1);
+        assert!(chunks[0].data.is_empty());
+        assert!(chunks[0].is_last);
+    }
+}
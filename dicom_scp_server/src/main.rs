@@ -1,13 +1,18 @@
+mod commitment;
 mod config;
 mod database;
+mod dimse;
+mod query;
 mod storage;
 mod scp;
+mod web;
 
 use config::Config;
 use database::Database;
 use storage::StorageHandler;
 use scp::DicomScpServer;
 use sqlx::postgres::PgPoolOptions;
+use std::sync::Arc;
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 
 #[tokio::main]
@@ -39,8 +44,18 @@ async fn main() -> anyhow::Result<()> {
 
     tracing::info!("Database connected successfully");
 
-    let database = Database::new(pool);
-    let storage = StorageHandler::new(config.storage.clone());
+    let database = Arc::new(Database::new(pool));
+    let storage = Arc::new(StorageHandler::new(config.storage.clone()));
+
+    // Start the DICOMweb HTTP surface alongside the DIMSE listener.
+    let web_bind_addr = format!("{}:{}", config.server.http_host, config.server.http_port);
+    let web_database = Arc::clone(&database);
+    let web_storage = Arc::clone(&storage);
+    tokio::spawn(async move {
+        if let Err(e) = web::start(web_bind_addr, web_database, web_storage).await {
+            tracing::error!("DICOMweb HTTP server error: {}", e);
+        }
+    });
 
     // Create and start server
     let server = DicomScpServer::new(config, database, storage);
@@ -0,0 +1,338 @@
+//! C-FIND query identifier parsing and matching support.
+//!
+//! Translates the identifier data set of a C-FIND-RQ into a structured
+//! [`FindQuery`] that `Database` can turn into parameterized SQL, and turns
+//! database rows back into DICOM result identifiers.
+
+use crate::dimse;
+use anyhow::Result;
+use dicom_core::header::DataElement;
+use dicom_core::{PrimitiveValue, VR};
+use dicom_object::InMemDicomObject;
+
+/// QueryRetrieveLevel (0008,0052).
+const TAG_QUERY_RETRIEVE_LEVEL: dicom_core::Tag = dicom_core::Tag(0x0008, 0x0052);
+const TAG_PATIENT_ID: dicom_core::Tag = dicom_core::Tag(0x0010, 0x0020);
+const TAG_PATIENT_NAME: dicom_core::Tag = dicom_core::Tag(0x0010, 0x0010);
+const TAG_PATIENT_BIRTH_DATE: dicom_core::Tag = dicom_core::Tag(0x0010, 0x0030);
+const TAG_PATIENT_SEX: dicom_core::Tag = dicom_core::Tag(0x0010, 0x0040);
+const TAG_STUDY_DATE: dicom_core::Tag = dicom_core::Tag(0x0008, 0x0020);
+const TAG_STUDY_TIME: dicom_core::Tag = dicom_core::Tag(0x0008, 0x0030);
+const TAG_ACCESSION_NUMBER: dicom_core::Tag = dicom_core::Tag(0x0008, 0x0050);
+const TAG_STUDY_INSTANCE_UID: dicom_core::Tag = dicom_core::Tag(0x0020, 0x000D);
+const TAG_STUDY_DESCRIPTION: dicom_core::Tag = dicom_core::Tag(0x0008, 0x1030);
+const TAG_MODALITY: dicom_core::Tag = dicom_core::Tag(0x0008, 0x0060);
+const TAG_SERIES_INSTANCE_UID: dicom_core::Tag = dicom_core::Tag(0x0020, 0x000E);
+const TAG_SERIES_NUMBER: dicom_core::Tag = dicom_core::Tag(0x0020, 0x0011);
+const TAG_SERIES_DESCRIPTION: dicom_core::Tag = dicom_core::Tag(0x0008, 0x103E);
+const TAG_SPECIFIC_CHARACTER_SET: dicom_core::Tag = dicom_core::Tag(0x0008, 0x0005);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QueryRetrieveLevel {
+    Patient,
+    Study,
+    Series,
+}
+
+/// An inclusive date range parsed from a DICOM DA range match
+/// (`YYYYMMDD`, `YYYYMMDD-`, `-YYYYMMDD`, or `YYYYMMDD-YYYYMMDD`).
+#[derive(Debug, Clone, Default)]
+pub struct DateRange {
+    pub from: Option<String>,
+    pub to: Option<String>,
+}
+
+/// A single matching key: a universal match (key present but empty, or
+/// entirely absent) is represented as `None`.
+pub type MatchKey = Option<String>;
+
+/// The parsed identifier of a C-FIND-RQ, ready to be turned into SQL.
+#[derive(Debug, Clone, Default)]
+pub struct FindQuery {
+    pub level: Option<QueryRetrieveLevel>,
+    pub patient_id: MatchKey,
+    pub patient_name: MatchKey,
+    pub study_instance_uid: MatchKey,
+    pub accession_number: MatchKey,
+    pub modality: MatchKey,
+    pub study_date: Option<DateRange>,
+    pub series_instance_uid: MatchKey,
+}
+
+impl FindQuery {
+    /// Build a `FindQuery` from DICOMweb QIDO-RS query parameters, which use
+    /// the same matching keys as C-FIND but as plain `key=value` pairs.
+    pub fn from_query_params(params: &std::collections::HashMap<String, String>) -> Self {
+        let non_empty = |key: &str| params.get(key).cloned().filter(|v| !v.is_empty());
+
+        FindQuery {
+            level: None,
+            patient_id: non_empty("PatientID"),
+            patient_name: non_empty("PatientName"),
+            study_instance_uid: non_empty("StudyInstanceUID"),
+            accession_number: non_empty("AccessionNumber"),
+            modality: non_empty("Modality"),
+            study_date: non_empty("StudyDate").map(|v| parse_date_range(&v)),
+            series_instance_uid: non_empty("SeriesInstanceUID"),
+        }
+    }
+
+    pub fn from_identifier(identifier: &InMemDicomObject) -> Result<Self> {
+        let level = match dimse::get_str(identifier, TAG_QUERY_RETRIEVE_LEVEL).ok().as_deref() {
+            Some("PATIENT") => Some(QueryRetrieveLevel::Patient),
+            Some("SERIES") => Some(QueryRetrieveLevel::Series),
+            Some("STUDY") | None => Some(QueryRetrieveLevel::Study),
+            Some(_) => Some(QueryRetrieveLevel::Study),
+        };
+
+        Ok(FindQuery {
+            level,
+            patient_id: universal_match(identifier, TAG_PATIENT_ID),
+            patient_name: universal_match(identifier, TAG_PATIENT_NAME),
+            study_instance_uid: universal_match(identifier, TAG_STUDY_INSTANCE_UID),
+            accession_number: universal_match(identifier, TAG_ACCESSION_NUMBER),
+            modality: universal_match(identifier, TAG_MODALITY),
+            study_date: universal_match(identifier, TAG_STUDY_DATE).map(|v| parse_date_range(&v)),
+            series_instance_uid: universal_match(identifier, TAG_SERIES_INSTANCE_UID),
+        })
+    }
+}
+
+/// Read a matching key, treating absence or an empty value as "universal
+/// matching" (the key is not used to filter results).
+fn universal_match(identifier: &InMemDicomObject, tag: dicom_core::Tag) -> MatchKey {
+    dimse::get_str(identifier, tag)
+        .ok()
+        .filter(|v| !v.is_empty())
+}
+
+/// Translate a DICOM wildcard-match pattern (`*` and `?`) into a SQL `LIKE`
+/// pattern (`%` and `_`).
+pub fn to_sql_like(pattern: &str) -> String {
+    pattern.replace('%', "\\%").replace('_', "\\_").replace('*', "%").replace('?', "_")
+}
+
+fn parse_date_range(value: &str) -> DateRange {
+    match value.split_once('-') {
+        Some((from, to)) => DateRange {
+            from: Some(from.to_string()).filter(|s| !s.is_empty()),
+            to: Some(to.to_string()).filter(|s| !s.is_empty()),
+        },
+        None => DateRange {
+            from: Some(value.to_string()),
+            to: Some(value.to_string()),
+        },
+    }
+}
+
+/// A single study-level result row, populated from `worklist_patient` and
+/// `worklist_study`.
+#[derive(Debug, Clone, Default)]
+pub struct StudyRow {
+    pub patient_id: String,
+    pub patient_name: String,
+    pub patient_birth_date: Option<String>,
+    pub patient_sex: Option<String>,
+    pub study_instance_uid: String,
+    pub study_date: Option<String>,
+    pub study_time: Option<String>,
+    pub accession_number: Option<String>,
+    pub study_description: Option<String>,
+    pub modality: Option<String>,
+}
+
+/// A single patient-level result row, populated from `worklist_patient`
+/// alone (no study/series attributes).
+#[derive(Debug, Clone, Default)]
+pub struct PatientRow {
+    pub patient_id: String,
+    pub patient_name: String,
+    pub patient_birth_date: Option<String>,
+    pub patient_sex: Option<String>,
+}
+
+/// A single series-level result row, populated from `worklist_series`.
+#[derive(Debug, Clone, Default)]
+pub struct SeriesRow {
+    pub study_instance_uid: String,
+    pub series_instance_uid: String,
+    pub series_number: Option<i32>,
+    pub series_description: Option<String>,
+    pub modality: Option<String>,
+}
+
+/// A single instance-level result row, populated from `worklist_dicomimage`.
+#[derive(Debug, Clone, Default)]
+pub struct InstanceRow {
+    pub sop_instance_uid: String,
+    pub sop_class_uid: String,
+    pub instance_number: Option<i32>,
+    pub dicom_file: String,
+    pub transfer_syntax_uid: String,
+}
+
+/// Build a C-FIND-RSP result identifier for a matched study, echoing the
+/// SpecificCharacterSet of the request if present.
+pub fn study_result_identifier(row: &StudyRow, request: &InMemDicomObject) -> InMemDicomObject {
+    let mut identifier = InMemDicomObject::new_empty();
+    copy_character_set(request, &mut identifier);
+
+    put_str(&mut identifier, TAG_PATIENT_ID, &row.patient_id);
+    put_str(&mut identifier, TAG_PATIENT_NAME, &row.patient_name);
+    if let Some(v) = &row.patient_birth_date {
+        put_str(&mut identifier, TAG_PATIENT_BIRTH_DATE, v);
+    }
+    if let Some(v) = &row.patient_sex {
+        put_str(&mut identifier, TAG_PATIENT_SEX, v);
+    }
+    put_str(&mut identifier, TAG_STUDY_INSTANCE_UID, &row.study_instance_uid);
+    if let Some(v) = &row.study_date {
+        put_str(&mut identifier, TAG_STUDY_DATE, v);
+    }
+    if let Some(v) = &row.study_time {
+        put_str(&mut identifier, TAG_STUDY_TIME, v);
+    }
+    if let Some(v) = &row.accession_number {
+        put_str(&mut identifier, TAG_ACCESSION_NUMBER, v);
+    }
+    if let Some(v) = &row.study_description {
+        put_str(&mut identifier, TAG_STUDY_DESCRIPTION, v);
+    }
+    if let Some(v) = &row.modality {
+        put_str(&mut identifier, TAG_MODALITY, v);
+    }
+    identifier
+}
+
+/// Build a C-FIND-RSP result identifier for a matched patient. Omits
+/// study-level tags (StudyInstanceUID, StudyDate, ...), which have no place
+/// in a PATIENT-level identifier.
+pub fn patient_result_identifier(row: &PatientRow, request: &InMemDicomObject) -> InMemDicomObject {
+    let mut identifier = InMemDicomObject::new_empty();
+    copy_character_set(request, &mut identifier);
+
+    put_str(&mut identifier, TAG_PATIENT_ID, &row.patient_id);
+    put_str(&mut identifier, TAG_PATIENT_NAME, &row.patient_name);
+    if let Some(v) = &row.patient_birth_date {
+        put_str(&mut identifier, TAG_PATIENT_BIRTH_DATE, v);
+    }
+    if let Some(v) = &row.patient_sex {
+        put_str(&mut identifier, TAG_PATIENT_SEX, v);
+    }
+    identifier
+}
+
+pub fn series_result_identifier(row: &SeriesRow, request: &InMemDicomObject) -> InMemDicomObject {
+    let mut identifier = InMemDicomObject::new_empty();
+    copy_character_set(request, &mut identifier);
+
+    put_str(&mut identifier, TAG_STUDY_INSTANCE_UID, &row.study_instance_uid);
+    put_str(&mut identifier, TAG_SERIES_INSTANCE_UID, &row.series_instance_uid);
+    if let Some(v) = row.series_number {
+        put_str(&mut identifier, TAG_SERIES_NUMBER, &v.to_string());
+    }
+    if let Some(v) = &row.series_description {
+        put_str(&mut identifier, TAG_SERIES_DESCRIPTION, v);
+    }
+    if let Some(v) = &row.modality {
+        put_str(&mut identifier, TAG_MODALITY, v);
+    }
+    identifier
+}
+
+fn copy_character_set(request: &InMemDicomObject, identifier: &mut InMemDicomObject) {
+    if let Ok(cs) = dimse::get_str(request, TAG_SPECIFIC_CHARACTER_SET) {
+        put_str(identifier, TAG_SPECIFIC_CHARACTER_SET, &cs);
+    }
+}
+
+fn put_str(obj: &mut InMemDicomObject, tag: dicom_core::Tag, value: &str) {
+    obj.put(DataElement::new(tag, VR::LO, PrimitiveValue::from(value)));
+}
+
+/// Build a DICOM JSON (PS3.18 Annex F) attribute object, e.g.
+/// `{"vr":"UI","Value":["1.2.3"]}`.
+fn json_attr(vr: &str, value: Option<&str>) -> serde_json::Value {
+    match value {
+        Some(v) if !v.is_empty() => serde_json::json!({ "vr": vr, "Value": [v] }),
+        _ => serde_json::json!({ "vr": vr }),
+    }
+}
+
+/// Render a study row as a DICOM JSON response object for QIDO-RS.
+pub fn study_row_to_json(row: &StudyRow) -> serde_json::Value {
+    serde_json::json!({
+        "00100020": json_attr("LO", Some(&row.patient_id)),
+        "00100010": json_attr("PN", Some(&row.patient_name)),
+        "0020000D": json_attr("UI", Some(&row.study_instance_uid)),
+        "00080020": json_attr("DA", row.study_date.as_deref()),
+        "00080030": json_attr("TM", row.study_time.as_deref()),
+        "00080050": json_attr("SH", row.accession_number.as_deref()),
+        "00081030": json_attr("LO", row.study_description.as_deref()),
+        "00080060": json_attr("CS", row.modality.as_deref()),
+    })
+}
+
+/// Render a series row as a DICOM JSON response object for QIDO-RS.
+pub fn series_row_to_json(row: &SeriesRow) -> serde_json::Value {
+    serde_json::json!({
+        "0020000D": json_attr("UI", Some(&row.study_instance_uid)),
+        "0020000E": json_attr("UI", Some(&row.series_instance_uid)),
+        "00200011": json_attr("IS", row.series_number.map(|n| n.to_string()).as_deref()),
+        "0008103E": json_attr("LO", row.series_description.as_deref()),
+        "00080060": json_attr("CS", row.modality.as_deref()),
+    })
+}
+
+/// Render an instance row as a DICOM JSON response object for QIDO-RS.
+pub fn instance_row_to_json(row: &InstanceRow) -> serde_json::Value {
+    serde_json::json!({
+        "00080016": json_attr("UI", Some(&row.sop_class_uid)),
+        "00080018": json_attr("UI", Some(&row.sop_instance_uid)),
+        "00200013": json_attr("IS", row.instance_number.map(|n| n.to_string()).as_deref()),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_date_range_single_date_matches_both_ends() {
+        let range = parse_date_range("20240101");
+        assert_eq!(range.from.as_deref(), Some("20240101"));
+        assert_eq!(range.to.as_deref(), Some("20240101"));
+    }
+
+    #[test]
+    fn parse_date_range_closed_range() {
+        let range = parse_date_range("20240101-20241231");
+        assert_eq!(range.from.as_deref(), Some("20240101"));
+        assert_eq!(range.to.as_deref(), Some("20241231"));
+    }
+
+    #[test]
+    fn parse_date_range_open_ended_from() {
+        let range = parse_date_range("20240101-");
+        assert_eq!(range.from.as_deref(), Some("20240101"));
+        assert_eq!(range.to, None);
+    }
+
+    #[test]
+    fn parse_date_range_open_ended_to() {
+        let range = parse_date_range("-20241231");
+        assert_eq!(range.from, None);
+        assert_eq!(range.to.as_deref(), Some("20241231"));
+    }
+
+    #[test]
+    fn to_sql_like_translates_wildcards() {
+        assert_eq!(to_sql_like("SMITH*"), "SMITH%");
+        assert_eq!(to_sql_like("J?N"), "J_N");
+    }
+
+    #[test]
+    fn to_sql_like_escapes_literal_percent_and_underscore() {
+        assert_eq!(to_sql_like("100%_done"), "100\\%\\_done");
+    }
+}
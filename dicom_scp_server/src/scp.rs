@@ -1,32 +1,45 @@
+use crate::commitment::{self, PendingCommitment};
 use crate::config::Config;
 use crate::database::Database;
+use crate::dimse::{self, command_field, status};
+use crate::query::{self, FindQuery, QueryRetrieveLevel};
 use crate::storage::StorageHandler;
-use dicom_object::InMemDicomObject;
 use dicom_ul::pdu::Pdu;
+use dicom_ul::association::client::ClientAssociationOptions;
 use dicom_ul::association::server::{ServerAssociationOptions};
+use std::collections::HashMap;
 use std::sync::Arc;
+use std::time::Duration;
 use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::Mutex;
 use tracing::{info, error, warn};
 use anyhow::Result;
 
+/// How often we retry delivering a queued (deferred) Storage Commitment
+/// N-EVENT-REPORT that could not be sent on its original association.
+const DEFERRED_COMMITMENT_RETRY_INTERVAL: Duration = Duration::from_secs(30);
+const MAX_DEFERRED_COMMITMENT_ATTEMPTS: u32 = 5;
+
 pub struct DicomScpServer {
     config: Arc<Config>,
     database: Arc<Database>,
     storage: Arc<StorageHandler>,
+    pending_commitments: Arc<Mutex<Vec<PendingCommitment>>>,
 }
 
 impl DicomScpServer {
-    pub fn new(config: Config, database: Database, storage: StorageHandler) -> Self {
+    pub fn new(config: Config, database: Arc<Database>, storage: Arc<StorageHandler>) -> Self {
         Self {
             config: Arc::new(config),
-            database: Arc::new(database),
-            storage: Arc::new(storage),
+            database,
+            storage,
+            pending_commitments: Arc::new(Mutex::new(Vec::new())),
         }
     }
 
     pub async fn start(&self) -> Result<()> {
         let bind_addr = format!("{}:{}", self.config.server.host, self.config.server.port);
-        
+
         info!("Starting DICOM SCP Server");
         info!("AE Title: {}", self.config.server.ae_title);
         info!("Listening on: {}", bind_addr);
@@ -34,6 +47,12 @@ impl DicomScpServer {
         let listener = TcpListener::bind(&bind_addr).await?;
         info!("Server started successfully!");
 
+        let deferred_config = Arc::clone(&self.config);
+        let deferred_queue = Arc::clone(&self.pending_commitments);
+        tokio::spawn(async move {
+            flush_deferred_commitments_loop(deferred_config, deferred_queue).await;
+        });
+
         loop {
             match listener.accept().await {
                 Ok((stream, addr)) => {
@@ -41,9 +60,12 @@ impl DicomScpServer {
                     let config = Arc::clone(&self.config);
                     let database = Arc::clone(&self.database);
                     let storage = Arc::clone(&self.storage);
+                    let pending_commitments = Arc::clone(&self.pending_commitments);
 
                     tokio::spawn(async move {
-                        if let Err(e) = handle_association(stream, config, database, storage).await {
+                        if let Err(e) =
+                            handle_association(stream, addr, config, database, storage, pending_commitments).await
+                        {
                             error!("Error handling association from {}: {}", addr, e);
                         }
                     });
@@ -58,9 +80,11 @@ impl DicomScpServer {
 
 async fn handle_association(
     mut stream: TcpStream,
+    peer_addr: std::net::SocketAddr,
     config: Arc<Config>,
     database: Arc<Database>,
     storage: Arc<StorageHandler>,
+    pending_commitments: Arc<Mutex<Vec<PendingCommitment>>>,
 ) -> Result<()> {
     // Create association options with supported presentation contexts
     let mut options = ServerAssociationOptions::new()
@@ -102,6 +126,28 @@ async fn handle_association(
         }
     }
 
+    // Query/Retrieve FIND SOP classes, negotiated with Implicit/Explicit VR
+    // Little Endian only (no compressed transfer syntaxes carry identifiers).
+    let find_sops = vec![
+        "1.2.840.10008.5.1.4.1.2.1.1", // Patient Root Query/Retrieve - FIND
+        "1.2.840.10008.5.1.4.1.2.2.1", // Study Root Query/Retrieve - FIND
+    ];
+    for sop in &find_sops {
+        options = options
+            .with_abstract_syntax(*sop)
+            .with_transfer_syntax("1.2.840.10008.1.2")
+            .with_abstract_syntax(*sop)
+            .with_transfer_syntax("1.2.840.10008.1.2.1");
+    }
+
+    // Storage Commitment Push Model, so modalities can confirm archival
+    // before deleting their local copies.
+    options = options
+        .with_abstract_syntax(commitment::SOP_CLASS_UID)
+        .with_transfer_syntax("1.2.840.10008.1.2")
+        .with_abstract_syntax(commitment::SOP_CLASS_UID)
+        .with_transfer_syntax("1.2.840.10008.1.2.1");
+
     // Accept association
     use dicom_ul::association::server::ServerAssociation;
     let mut scu = match ServerAssociation::accept(stream, options).await {
@@ -114,6 +160,20 @@ async fn handle_association(
             return Err(e.into());
         }
     };
+    let calling_ae_title = scu.client_ae_title().to_string();
+
+    // Map of accepted presentation context ID -> negotiated transfer syntax,
+    // used to decode the data set that follows a DIMSE command.
+    let context_transfer_syntaxes: HashMap<u8, String> = scu
+        .presentation_contexts()
+        .iter()
+        .map(|pc| (pc.id, pc.transfer_syntax.clone()))
+        .collect();
+
+    // Per-presentation-context fragment buffers; a DIMSE command (and its
+    // data set, if any) can be split across many P-DATA-TF PDUs.
+    let mut assemblers: HashMap<u8, dimse::FragmentAssembler> = HashMap::new();
+    let max_pdu_length = config.server.max_pdu_length as usize;
 
     // Handle incoming messages
     loop {
@@ -123,13 +183,34 @@ async fn handle_association(
                     Pdu::PData { data } => {
                         // Handle C-STORE or C-ECHO
                         for pdata_value in data {
-                            if let Err(e) = handle_pdata(
+                            let context_id = pdata_value.presentation_context_id;
+                            let assembler = assemblers.entry(context_id).or_default();
+                            assembler.push(&pdata_value);
+
+                            let message_complete = assembler.command_ready()
+                                && match dimse::parse_command_set(assembler.command_bytes()) {
+                                    Ok(cmd) => !dimse::has_data_set(&cmd) || assembler.data_ready(),
+                                    Err(_) => false,
+                                };
+
+                            if !message_complete {
+                                continue;
+                            }
+
+                            let assembler = assemblers.remove(&context_id).unwrap_or_default();
+                            if let Err(e) = handle_dimse_message(
                                 &mut scu,
-                                pdata_value,
+                                context_id,
+                                assembler,
+                                &context_transfer_syntaxes,
                                 &database,
                                 &storage,
+                                max_pdu_length,
+                                peer_addr,
+                                &calling_ae_title,
+                                &pending_commitments,
                             ).await {
-                                error!("Error handling P-DATA: {}", e);
+                                error!("Error handling DIMSE message: {}", e);
                             }
                         }
                     }
@@ -158,59 +239,384 @@ async fn handle_association(
     Ok(())
 }
 
-async fn handle_pdata(
+/// Handle one fully-reassembled DIMSE message: dispatch on CommandField and,
+/// for operations that expect a response, send it back on the same
+/// presentation context.
+async fn handle_dimse_message(
     scu: &mut dicom_ul::association::server::ServerAssociation,
-    pdata: dicom_ul::pdu::PDataValue,
+    context_id: u8,
+    assembler: dimse::FragmentAssembler,
+    context_transfer_syntaxes: &HashMap<u8, String>,
     database: &Database,
     storage: &StorageHandler,
+    max_pdu_length: usize,
+    peer_addr: std::net::SocketAddr,
+    calling_ae_title: &str,
+    pending_commitments: &Arc<Mutex<Vec<PendingCommitment>>>,
 ) -> Result<()> {
-    use dicom_ul::pdu::ValueType;
-
-    match pdata.value_type {
-        ValueType::Command => {
-            // Handle DIMSE command (simplified - just acknowledge)
-            info!("Received DIMSE command");
-        }
-        ValueType::Data => {
-            // This is DICOM dataset - try to parse and store
-            match InMemDicomObject::read_dataset(&pdata.data[..]) {
-                Ok(obj) => {
-                    info!("Received DICOM object");
-                    
-                    // Store to filesystem
-                    match storage.store_dicom(&obj) {
-                        Ok(file_path) => {
-                            info!("Stored DICOM file: {:?}", file_path);
-                            
-                            // Get file size
-                            let file_size = storage.get_file_size(&file_path)
-                                .unwrap_or(0) as i64;
-                            
-                            // Store metadata to database
-                            match database.store_dicom_metadata(
-                                &obj,
-                                file_path.to_str().unwrap_or(""),
-                                file_size
-                            ).await {
-                                Ok(_) => {
-                                    info!("Stored DICOM metadata to database");
-                                }
-                                Err(e) => {
-                                    error!("Failed to store metadata: {}", e);
-                                }
-                            }
-                        }
-                        Err(e) => {
-                            error!("Failed to store DICOM file: {}", e);
-                        }
-                    }
+    let command = dimse::parse_command_set(assembler.command_bytes())?;
+    let field = dimse::command_field_of(&command)?;
+
+    match field {
+        command_field::C_STORE_RQ => {
+            let transfer_syntax = context_transfer_syntaxes
+                .get(&context_id)
+                .cloned()
+                .unwrap_or_else(|| dimse::IMPLICIT_VR_LE.to_string());
+
+            let status_code = handle_c_store(
+                &command,
+                assembler.data_bytes(),
+                &transfer_syntax,
+                database,
+                storage,
+            )
+            .await;
+
+            let rsp = dimse::build_cstore_rsp(&command, status_code)?;
+            let pdvs = dimse::encode_command(&rsp, context_id, max_pdu_length)?;
+            scu.send(&Pdu::PData { data: pdvs }).await?;
+        }
+        command_field::C_FIND_RQ => {
+            let transfer_syntax = context_transfer_syntaxes
+                .get(&context_id)
+                .cloned()
+                .unwrap_or_else(|| dimse::IMPLICIT_VR_LE.to_string());
+
+            handle_c_find(
+                scu,
+                &command,
+                assembler.data_bytes(),
+                &transfer_syntax,
+                context_id,
+                database,
+                max_pdu_length,
+            )
+            .await?;
+        }
+        command_field::N_ACTION_RQ => {
+            let transfer_syntax = context_transfer_syntaxes
+                .get(&context_id)
+                .cloned()
+                .unwrap_or_else(|| dimse::IMPLICIT_VR_LE.to_string());
+
+            handle_n_action(
+                scu,
+                &command,
+                assembler.data_bytes(),
+                &transfer_syntax,
+                context_id,
+                database,
+                max_pdu_length,
+                peer_addr,
+                calling_ae_title,
+                pending_commitments,
+            )
+            .await?;
+        }
+        other => {
+            warn!("Unsupported DIMSE command field: 0x{:04X}", other);
+        }
+    }
+
+    Ok(())
+}
+
+/// Answer a C-FIND-RQ: match the identifier against the metadata database
+/// and send one Pending C-FIND-RSP per result, then a final Success
+/// response.
+async fn handle_c_find(
+    scu: &mut dicom_ul::association::server::ServerAssociation,
+    command: &dicom_object::InMemDicomObject,
+    identifier_bytes: &[u8],
+    transfer_syntax: &str,
+    context_id: u8,
+    database: &Database,
+    max_pdu_length: usize,
+) -> Result<()> {
+    let identifier = dimse::parse_data_set(identifier_bytes, transfer_syntax)?;
+    let find_query = FindQuery::from_identifier(&identifier)?;
+    info!("C-FIND query: {:?}", find_query);
+
+    match find_query.level {
+        Some(QueryRetrieveLevel::Patient) => {
+            let rows = database.find_patients(&find_query).await?;
+            for row in &rows {
+                let result = query::patient_result_identifier(row, &identifier);
+                send_cfind_pending(scu, command, &result, transfer_syntax, context_id, max_pdu_length).await?;
+            }
+        }
+        Some(QueryRetrieveLevel::Series) => {
+            let rows = database.find_series(&find_query).await?;
+            for row in &rows {
+                let result = query::series_result_identifier(row, &identifier);
+                send_cfind_pending(scu, command, &result, transfer_syntax, context_id, max_pdu_length).await?;
+            }
+        }
+        Some(QueryRetrieveLevel::Study) | None => {
+            let rows = database.find_studies(&find_query).await?;
+            for row in &rows {
+                let result = query::study_result_identifier(row, &identifier);
+                send_cfind_pending(scu, command, &result, transfer_syntax, context_id, max_pdu_length).await?;
+            }
+        }
+    }
+
+    let final_rsp = dimse::build_cfind_rsp(command, status::SUCCESS)?;
+    let pdvs = dimse::encode_command(&final_rsp, context_id, max_pdu_length)?;
+    scu.send(&Pdu::PData { data: pdvs }).await?;
+    Ok(())
+}
+
+async fn send_cfind_pending(
+    scu: &mut dicom_ul::association::server::ServerAssociation,
+    command: &dicom_object::InMemDicomObject,
+    result: &dicom_object::InMemDicomObject,
+    transfer_syntax: &str,
+    context_id: u8,
+    max_pdu_length: usize,
+) -> Result<()> {
+    let rsp = dimse::build_cfind_rsp(command, status::PENDING)?;
+    let mut pdvs = dimse::encode_command(&rsp, context_id, max_pdu_length)?;
+    pdvs.extend(dimse::encode_data_set(result, transfer_syntax, context_id, max_pdu_length)?);
+    scu.send(&Pdu::PData { data: pdvs }).await?;
+    Ok(())
+}
+
+/// Decode and persist the data set carried by a C-STORE-RQ, returning the
+/// DIMSE status to report back to the SCU.
+async fn handle_c_store(
+    command: &dicom_object::InMemDicomObject,
+    data_bytes: &[u8],
+    transfer_syntax: &str,
+    database: &Database,
+    storage: &StorageHandler,
+) -> u16 {
+    let sop_instance_uid = dimse::get_str(command, dimse::tag::AFFECTED_SOP_INSTANCE_UID)
+        .unwrap_or_else(|_| "<unknown>".to_string());
+
+    let obj = match dimse::parse_data_set(data_bytes, transfer_syntax) {
+        Ok(obj) => obj,
+        Err(e) => {
+            error!("Failed to parse DICOM data set for {}: {}", sop_instance_uid, e);
+            return status::CANNOT_UNDERSTAND;
+        }
+    };
+
+    info!("Received C-STORE for SOP Instance {}", sop_instance_uid);
+
+    let obj = storage.maybe_transcode(obj);
+
+    let location = match storage.store_dicom(&obj).await {
+        Ok(location) => location,
+        Err(e) => {
+            error!("Failed to store DICOM file for {}: {}", sop_instance_uid, e);
+            return status::OUT_OF_RESOURCES;
+        }
+    };
+    info!("Stored DICOM object: {}", location.0);
+
+    let file_size = storage.get_size(&obj).await.unwrap_or(0) as i64;
+
+    match database
+        .store_dicom_metadata(&obj, &location.0, file_size)
+        .await
+    {
+        Ok(_) => {
+            info!("Stored DICOM metadata to database");
+            status::SUCCESS
+        }
+        Err(e) => {
+            error!("Failed to store metadata for {}: {}", sop_instance_uid, e);
+            status::OUT_OF_RESOURCES
+        }
+    }
+}
+
+/// Answer a Storage Commitment N-ACTION-RQ: reply immediately with
+/// N-ACTION-RSP, verify each referenced instance against the archive, then
+/// report the outcome via N-EVENT-REPORT-RQ on the same association (or,
+/// if that fails, queue it for deferred delivery).
+#[allow(clippy::too_many_arguments)]
+async fn handle_n_action(
+    scu: &mut dicom_ul::association::server::ServerAssociation,
+    command: &dicom_object::InMemDicomObject,
+    identifier_bytes: &[u8],
+    transfer_syntax: &str,
+    context_id: u8,
+    database: &Database,
+    max_pdu_length: usize,
+    peer_addr: std::net::SocketAddr,
+    calling_ae_title: &str,
+    pending_commitments: &Arc<Mutex<Vec<PendingCommitment>>>,
+) -> Result<()> {
+    let identifier = dimse::parse_data_set(identifier_bytes, transfer_syntax)?;
+
+    if !commitment::is_commitment_request(command) {
+        warn!("N-ACTION-RQ with unsupported ActionTypeID; ignoring");
+        let rsp = dimse::build_naction_rsp(command, status::CANNOT_UNDERSTAND)?;
+        let pdvs = dimse::encode_command(&rsp, context_id, max_pdu_length)?;
+        scu.send(&Pdu::PData { data: pdvs }).await?;
+        return Ok(());
+    }
+
+    let transaction_uid = commitment::transaction_uid(&identifier)?;
+    let referenced = commitment::parse_referenced_sops(&identifier)?;
+
+    let rsp = dimse::build_naction_rsp(command, status::SUCCESS)?;
+    let pdvs = dimse::encode_command(&rsp, context_id, max_pdu_length)?;
+    scu.send(&Pdu::PData { data: pdvs }).await?;
+
+    let mut committed = Vec::new();
+    let mut failed = Vec::new();
+    for sop in referenced {
+        match database.instance_exists(&sop.sop_instance_uid).await {
+            Ok(true) => committed.push(sop),
+            Ok(false) => failed.push(sop),
+            Err(e) => {
+                error!("Failed to verify instance {}: {}", sop.sop_instance_uid, e);
+                failed.push(sop);
+            }
+        }
+    }
+
+    let report_identifier =
+        commitment::build_event_report_identifier(&transaction_uid, &committed, &failed);
+    let event_type = commitment::event_type_id(&failed);
+    let report_message_id = dimse::get_u16(command, dimse::tag::MESSAGE_ID)
+        .unwrap_or(1)
+        .wrapping_add(1);
+    let report_command = dimse::build_event_report_rq(
+        report_message_id,
+        commitment::SOP_CLASS_UID,
+        commitment::SOP_INSTANCE_UID,
+        event_type,
+    )?;
+
+    let mut report_pdvs = dimse::encode_command(&report_command, context_id, max_pdu_length)?;
+    report_pdvs.extend(dimse::encode_data_set(
+        &report_identifier,
+        transfer_syntax,
+        context_id,
+        max_pdu_length,
+    )?);
+
+    if let Err(e) = scu.send(&Pdu::PData { data: report_pdvs }).await {
+        warn!(
+            "Failed to send N-EVENT-REPORT-RQ on the same association ({}); queuing for deferred delivery",
+            e
+        );
+        pending_commitments.lock().await.push(PendingCommitment {
+            peer_addr,
+            calling_ae_title: calling_ae_title.to_string(),
+            transaction_uid,
+            committed,
+            failed,
+            attempts: 0,
+        });
+    } else {
+        info!(
+            "Reported storage commitment for transaction {} ({} committed, {} failed)",
+            transaction_uid,
+            committed.len(),
+            failed.len()
+        );
+    }
+
+    Ok(())
+}
+
+/// Background task that periodically retries delivering queued Storage
+/// Commitment N-EVENT-REPORT-RQs that couldn't be sent on their original
+/// association, by opening a fresh outbound association back to the peer.
+async fn flush_deferred_commitments_loop(
+    config: Arc<Config>,
+    pending_commitments: Arc<Mutex<Vec<PendingCommitment>>>,
+) {
+    loop {
+        tokio::time::sleep(DEFERRED_COMMITMENT_RETRY_INTERVAL).await;
+
+        let due: Vec<PendingCommitment> = {
+            let mut queue = pending_commitments.lock().await;
+            std::mem::take(&mut *queue)
+        };
+        if due.is_empty() {
+            continue;
+        }
+
+        let mut still_pending = Vec::new();
+        for mut entry in due {
+            match deliver_deferred_commitment(&config, &entry).await {
+                Ok(()) => {
+                    info!(
+                        "Delivered deferred storage commitment report for transaction {}",
+                        entry.transaction_uid
+                    );
                 }
                 Err(e) => {
-                    error!("Failed to parse DICOM object: {}", e);
+                    entry.attempts += 1;
+                    if entry.attempts >= MAX_DEFERRED_COMMITMENT_ATTEMPTS {
+                        error!(
+                            "Giving up on deferred storage commitment report for transaction {} after {} attempts: {}",
+                            entry.transaction_uid, entry.attempts, e
+                        );
+                    } else {
+                        warn!(
+                            "Deferred storage commitment report for transaction {} failed (attempt {}): {}",
+                            entry.transaction_uid, entry.attempts, e
+                        );
+                        still_pending.push(entry);
+                    }
                 }
             }
         }
+
+        if !still_pending.is_empty() {
+            pending_commitments.lock().await.extend(still_pending);
+        }
     }
+}
+
+async fn deliver_deferred_commitment(config: &Config, entry: &PendingCommitment) -> Result<()> {
+    let stream = TcpStream::connect(entry.peer_addr).await?;
+    let options = ClientAssociationOptions::new()
+        .calling_ae_title(&config.server.ae_title)
+        .called_ae_title(&entry.calling_ae_title)
+        .with_presentation_context(
+            commitment::SOP_CLASS_UID,
+            vec![dimse::IMPLICIT_VR_LE.to_string()],
+        );
+
+    use dicom_ul::association::client::ClientAssociation;
+    let mut scu = ClientAssociation::new(stream, options).await?;
+    let context_id = scu
+        .presentation_contexts()
+        .first()
+        .map(|pc| pc.id)
+        .ok_or_else(|| anyhow::anyhow!("peer did not accept the Storage Commitment presentation context"))?;
+
+    let report_identifier = commitment::build_event_report_identifier(
+        &entry.transaction_uid,
+        &entry.committed,
+        &entry.failed,
+    );
+    let event_type = commitment::event_type_id(&entry.failed);
+    let report_command = dimse::build_event_report_rq(
+        1,
+        commitment::SOP_CLASS_UID,
+        commitment::SOP_INSTANCE_UID,
+        event_type,
+    )?;
+
+    let mut pdvs = dimse::encode_command(&report_command, context_id, config.server.max_pdu_length as usize)?;
+    pdvs.extend(dimse::encode_data_set(
+        &report_identifier,
+        dimse::IMPLICIT_VR_LE,
+        context_id,
+        config.server.max_pdu_length as usize,
+    )?);
 
+    scu.send(&Pdu::PData { data: pdvs }).await?;
+    scu.send(&Pdu::ReleaseRQ).await?;
     Ok(())
 }
\ No newline at end of file
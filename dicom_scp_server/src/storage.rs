@@ -1,79 +1,331 @@
-use crate::config::StorageConfig;
+use crate::config::{StorageBackendKind, StorageConfig};
+use async_trait::async_trait;
+use dicom_core::header::DataElement;
+use dicom_core::{PrimitiveValue, Tag, VR};
 use dicom_object::InMemDicomObject;
+use dicom_pixeldata::PixelDecoder;
+use dicom_transfer_syntax_registry::TransferSyntaxRegistry;
 use std::fs;
-use std::path::{Path, PathBuf};
-use anyhow::{Result, Context};
+use std::path::PathBuf;
+use anyhow::{anyhow, Result, Context};
+use tracing::warn;
+
+const PIXEL_DATA: Tag = Tag(0x7FE0, 0x0010);
+const PHOTOMETRIC_INTERPRETATION: Tag = Tag(0x0028, 0x0004);
+const PLANAR_CONFIGURATION: Tag = Tag(0x0028, 0x0006);
+
+/// Compressed transfer syntaxes we know how to decode for transcoding on
+/// ingest (JPEG Baseline, JPEG Lossless, JPEG 2000).
+const COMPRESSED_TRANSFER_SYNTAXES: &[&str] = &[
+    "1.2.840.10008.1.2.4.50",
+    "1.2.840.10008.1.2.4.70",
+    "1.2.840.10008.1.2.4.90",
+];
+
+/// Where a stored DICOM object ended up: a local filesystem path, or an
+/// object-store URI (`s3://bucket/key`) when storing through `S3Backend`.
+/// This is what gets written to the `dicom_file` database column.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Location(pub String);
+
+/// A place DICOM objects can be persisted and read back by key. The key is
+/// the hierarchical `patient/study/series/sop.dcm` path generated by
+/// `StorageHandler`, independent of how a given backend lays it out.
+#[async_trait]
+pub trait StorageBackend: Send + Sync {
+    async fn store(&self, obj: &InMemDicomObject, key: &str) -> Result<Location>;
+    async fn retrieve(&self, key: &str) -> Result<Vec<u8>>;
+    async fn size(&self, key: &str) -> Result<u64>;
+}
+
+/// Stores objects as files under a local base directory.
+pub struct FilesystemBackend {
+    base_path: PathBuf,
+}
+
+impl FilesystemBackend {
+    pub fn new(base_path: impl Into<PathBuf>) -> Self {
+        let base_path = base_path.into();
+        fs::create_dir_all(&base_path).ok();
+        Self { base_path }
+    }
+}
+
+#[async_trait]
+impl StorageBackend for FilesystemBackend {
+    async fn store(&self, obj: &InMemDicomObject, key: &str) -> Result<Location> {
+        let path = self.base_path.join(key);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        obj.write_to_file(&path).context("Failed to write DICOM file")?;
+        Ok(Location(path.to_string_lossy().to_string()))
+    }
+
+    async fn retrieve(&self, key: &str) -> Result<Vec<u8>> {
+        fs::read(self.base_path.join(key)).context("Failed to read DICOM file")
+    }
+
+    async fn size(&self, key: &str) -> Result<u64> {
+        Ok(fs::metadata(self.base_path.join(key))?.len())
+    }
+}
+
+/// Stores objects in an S3-compatible object store (AWS S3, MinIO, Garage),
+/// so the archive can run statelessly against shared, scalable storage.
+pub struct S3Backend {
+    client: aws_sdk_s3::Client,
+    bucket: String,
+}
+
+impl S3Backend {
+    pub fn new(config: &StorageConfig) -> Result<Self> {
+        let bucket = config
+            .bucket
+            .clone()
+            .context("storage.bucket must be set when storage.backend = \"s3\"")?;
+        let region = config
+            .region
+            .clone()
+            .unwrap_or_else(|| "us-east-1".to_string());
+
+        let mut builder = aws_sdk_s3::config::Builder::new()
+            .region(aws_sdk_s3::config::Region::new(region))
+            .force_path_style(true);
+
+        if let Some(endpoint) = &config.endpoint {
+            builder = builder.endpoint_url(endpoint);
+        }
+        if let (Some(access_key_id), Some(secret_access_key)) =
+            (&config.access_key_id, &config.secret_access_key)
+        {
+            builder = builder.credentials_provider(aws_sdk_s3::config::Credentials::new(
+                access_key_id,
+                secret_access_key,
+                None,
+                None,
+                "dicom_scp_server",
+            ));
+        }
+
+        Ok(Self {
+            client: aws_sdk_s3::Client::from_conf(builder.build()),
+            bucket,
+        })
+    }
+}
+
+#[async_trait]
+impl StorageBackend for S3Backend {
+    async fn store(&self, obj: &InMemDicomObject, key: &str) -> Result<Location> {
+        let mut buf = Vec::new();
+        obj.write_all(&mut buf)
+            .context("failed to serialize DICOM object for upload")?;
+
+        self.client
+            .put_object()
+            .bucket(&self.bucket)
+            .key(key)
+            .body(buf.into())
+            .send()
+            .await
+            .context("failed to PUT object to S3")?;
+
+        Ok(Location(format!("s3://{}/{}", self.bucket, key)))
+    }
+
+    async fn retrieve(&self, key: &str) -> Result<Vec<u8>> {
+        let output = self
+            .client
+            .get_object()
+            .bucket(&self.bucket)
+            .key(key)
+            .send()
+            .await
+            .context("failed to GET object from S3")?;
+        let bytes = output
+            .body
+            .collect()
+            .await
+            .context("failed to read S3 object body")?;
+        Ok(bytes.into_bytes().to_vec())
+    }
+
+    async fn size(&self, key: &str) -> Result<u64> {
+        let output = self
+            .client
+            .head_object()
+            .bucket(&self.bucket)
+            .key(key)
+            .send()
+            .await
+            .context("failed to HEAD object in S3")?;
+        Ok(output.content_length().unwrap_or(0) as u64)
+    }
+}
 
 pub struct StorageHandler {
     config: StorageConfig,
+    backend: Box<dyn StorageBackend>,
 }
 
 impl StorageHandler {
     pub fn new(config: StorageConfig) -> Self {
-        // Ensure base storage path exists
-        fs::create_dir_all(&config.base_path).ok();
-        Self { config }
+        let backend: Box<dyn StorageBackend> = match config.backend {
+            StorageBackendKind::Filesystem => Box::new(FilesystemBackend::new(&config.base_path)),
+            StorageBackendKind::S3 => Box::new(
+                S3Backend::new(&config).expect("failed to initialize S3 storage backend"),
+            ),
+        };
+        Self { config, backend }
     }
 
-    /// Generate file path based on DICOM metadata
-    pub fn generate_file_path(&self, obj: &InMemDicomObject) -> Result<PathBuf> {
-        let base_path = Path::new(&self.config.base_path);
-        let mut path = base_path.to_path_buf();
+    /// Generate the hierarchical storage key for a DICOM object, based on
+    /// the same organize-by-patient/study layout regardless of backend.
+    pub fn generate_key(&self, obj: &InMemDicomObject) -> String {
+        let mut segments: Vec<String> = Vec::new();
 
-        // Organize by patient if configured
         if self.config.organize_by_patient {
             if let Ok(elem) = obj.element_by_name("PatientID") {
                 if let Ok(patient_id) = elem.to_str() {
-                    path.push(sanitize_filename(patient_id));
+                    segments.push(sanitize_filename(&patient_id));
                 }
             }
         }
 
-        // Organize by study if configured
         if self.config.organize_by_study {
             if let Ok(elem) = obj.element_by_name("StudyInstanceUID") {
                 if let Ok(study_uid) = elem.to_str() {
-                    path.push(sanitize_filename(study_uid));
+                    segments.push(sanitize_filename(&study_uid));
                 }
             }
         }
 
-        // Add series directory
         if let Ok(elem) = obj.element_by_name("SeriesInstanceUID") {
             if let Ok(series_uid) = elem.to_str() {
-                path.push(sanitize_filename(series_uid));
+                segments.push(sanitize_filename(&series_uid));
             }
         }
 
-        // Create directories if they don't exist
-        fs::create_dir_all(&path)?;
+        let filename = match obj.element_by_name("SOPInstanceUID") {
+            Ok(elem) => match elem.to_str() {
+                Ok(sop_uid) => format!("{}.dcm", sanitize_filename(&sop_uid)),
+                Err(_) => "unknown.dcm".to_string(),
+            },
+            Err(_) => "unknown.dcm".to_string(),
+        };
+        segments.push(filename);
+
+        segments.join("/")
+    }
+
+    /// Normalize an incoming object to `StorageConfig.store_transfer_syntax`
+    /// when configured and the object arrived in a compressed transfer
+    /// syntax we can decode. Returns the object unchanged (storing it as
+    /// received) when transcoding is disabled, not needed, or fails.
+    pub fn maybe_transcode(&self, obj: InMemDicomObject) -> InMemDicomObject {
+        let Some(target_ts_uid) = self.config.store_transfer_syntax.as_deref() else {
+            return obj;
+        };
+
+        let current_ts_uid = obj.meta().transfer_syntax().trim_end_matches('\0').to_string();
+        if current_ts_uid == target_ts_uid {
+            return obj;
+        }
+        if !COMPRESSED_TRANSFER_SYNTAXES.contains(&current_ts_uid.as_str()) {
+            return obj;
+        }
 
-        // Add filename based on SOP Instance UID
-        if let Ok(elem) = obj.element_by_name("SOPInstanceUID") {
-            if let Ok(sop_uid) = elem.to_str() {
-                path.push(format!("{}.dcm", sanitize_filename(sop_uid)));
+        match transcode(&obj, target_ts_uid) {
+            Ok(transcoded) => transcoded,
+            Err(e) => {
+                warn!(
+                    "Failed to transcode from {} to {}: {} - storing original bytes",
+                    current_ts_uid, target_ts_uid, e
+                );
+                obj
             }
         }
+    }
 
-        Ok(path)
+    /// Store a DICOM object through the configured backend.
+    pub async fn store_dicom(&self, obj: &InMemDicomObject) -> Result<Location> {
+        let key = self.generate_key(obj);
+        self.backend.store(obj, &key).await
     }
 
-    /// Store DICOM object to file system
-    pub fn store_dicom(&self, obj: &InMemDicomObject) -> Result<PathBuf> {
-        let file_path = self.generate_file_path(obj)?;
-        
-        // Save the DICOM object
-        obj.write_to_file(&file_path)
-            .context("Failed to write DICOM file")?;
-        
-        Ok(file_path)
+    /// Size, in bytes, of a previously-stored object.
+    pub async fn get_size(&self, obj: &InMemDicomObject) -> Result<u64> {
+        let key = self.generate_key(obj);
+        self.backend.size(&key).await
     }
 
-    /// Get file size
-    pub fn get_file_size(&self, file_path: &Path) -> Result<u64> {
-        let metadata = fs::metadata(file_path)?;
-        Ok(metadata.len())
+    /// Read back the bytes of a previously-stored object from its recorded
+    /// location (a local path, or an `s3://bucket/key` URI), for WADO-RS.
+    pub async fn retrieve(&self, location: &str) -> Result<Vec<u8>> {
+        match self.config.backend {
+            StorageBackendKind::Filesystem => {
+                fs::read(location).context("failed to read stored DICOM file")
+            }
+            StorageBackendKind::S3 => {
+                let key = location
+                    .strip_prefix("s3://")
+                    .and_then(|rest| rest.split_once('/'))
+                    .map(|(_bucket, key)| key)
+                    .ok_or_else(|| anyhow!("invalid S3 location: {location}"))?;
+                self.backend.retrieve(key).await
+            }
+        }
+    }
+}
+
+/// Decode the pixel data of a compressed object and re-encode the object in
+/// `target_ts_uid`, updating the file meta transfer syntax so downstream
+/// readers (and the database row written in `create_instance`) see the
+/// stored form rather than the one received over the wire.
+fn transcode(obj: &InMemDicomObject, target_ts_uid: &str) -> Result<InMemDicomObject> {
+    TransferSyntaxRegistry
+        .get(target_ts_uid)
+        .ok_or_else(|| anyhow!("unknown transfer syntax: {target_ts_uid}"))?;
+
+    let decoded = obj
+        .decode_pixel_data()
+        .context("failed to decode compressed pixel data")?;
+    let raw_pixels = decoded
+        .data()
+        .context("failed to read decoded pixel samples")?;
+
+    let mut transcoded = obj.clone();
+
+    // PS3.5 10.2: PixelData must be OB when BitsAllocated <= 8, OW otherwise.
+    let pixel_data_vr = if decoded.bits_allocated <= 8 { VR::OB } else { VR::OW };
+    transcoded.put(DataElement::new(
+        PIXEL_DATA,
+        pixel_data_vr,
+        PrimitiveValue::from(raw_pixels),
+    ));
+
+    // The decoder normalizes YBR_* inputs to the photometric interpretation
+    // (and, for color, planar configuration) it actually produced - the
+    // stored tags must reflect that, not whatever was negotiated on the wire.
+    transcoded.put(DataElement::new(
+        PHOTOMETRIC_INTERPRETATION,
+        VR::CS,
+        PrimitiveValue::from(decoded.photometric_interpretation.to_string()),
+    ));
+    if decoded.samples_per_pixel > 1 {
+        transcoded.put(DataElement::new(
+            PLANAR_CONFIGURATION,
+            VR::US,
+            PrimitiveValue::from(decoded.planar_configuration as u16),
+        ));
     }
+
+    let mut meta = transcoded.meta().clone();
+    meta.transfer_syntax = target_ts_uid.to_string();
+    *transcoded.meta_mut() = meta;
+
+    Ok(transcoded)
 }
 
 /// Sanitize filename to remove invalid characters
@@ -99,4 +351,4 @@ mod tests {
         assert_eq!(sanitize_filename("1.2.3.4.5"), "1.2.3.4.5");
         assert_eq!(sanitize_filename("normal_name-123"), "normal_name-123");
     }
-}
\ No newline at end of file
+}
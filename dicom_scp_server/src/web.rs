@@ -0,0 +1,205 @@
+//! DICOMweb HTTP surface (QIDO-RS, WADO-RS, STOW-RS), run alongside the
+//! DIMSE `DicomScpServer` so browser viewers and other non-DIMSE clients can
+//! query and retrieve studies without a DICOM toolkit.
+
+use crate::database::Database;
+use crate::query::{self, FindQuery};
+use crate::storage::StorageHandler;
+use anyhow::Result;
+use axum::body::Bytes;
+use axum::extract::{Multipart, Path, Query, State};
+use axum::http::{header, StatusCode};
+use axum::response::IntoResponse;
+use axum::routing::get;
+use axum::{Json, Router};
+use serde_json::json;
+use std::collections::HashMap;
+use std::sync::Arc;
+use tracing::{error, info};
+
+const MULTIPART_BOUNDARY: &str = "dicom-scp-server-boundary";
+
+#[derive(Clone)]
+struct WebState {
+    database: Arc<Database>,
+    storage: Arc<StorageHandler>,
+}
+
+/// Start the DICOMweb HTTP listener. Runs until the process exits or the
+/// socket fails to bind.
+pub async fn start(
+    bind_addr: String,
+    database: Arc<Database>,
+    storage: Arc<StorageHandler>,
+) -> Result<()> {
+    let state = WebState { database, storage };
+
+    let app = Router::new()
+        .route("/studies", get(qido_studies).post(stow_studies))
+        .route("/studies/:study_uid/series", get(qido_series))
+        .route("/series/:series_uid/instances", get(qido_instances))
+        .route(
+            "/studies/:study_uid/series/:series_uid/instances/:sop_uid",
+            get(wado_instance),
+        )
+        .with_state(state);
+
+    info!("Starting DICOMweb HTTP server on: {}", bind_addr);
+    let listener = tokio::net::TcpListener::bind(&bind_addr).await?;
+    axum::serve(listener, app).await?;
+    Ok(())
+}
+
+/// QIDO-RS `GET /studies`.
+async fn qido_studies(
+    State(state): State<WebState>,
+    Query(params): Query<HashMap<String, String>>,
+) -> impl IntoResponse {
+    let find_query = FindQuery::from_query_params(&params);
+    match state.database.find_studies(&find_query).await {
+        Ok(rows) => {
+            let body: Vec<_> = rows.iter().map(query::study_row_to_json).collect();
+            Json(body).into_response()
+        }
+        Err(e) => {
+            error!("QIDO-RS /studies failed: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+/// QIDO-RS `GET /studies/{uid}/series`.
+async fn qido_series(
+    State(state): State<WebState>,
+    Path(study_uid): Path<String>,
+    Query(params): Query<HashMap<String, String>>,
+) -> impl IntoResponse {
+    let mut find_query = FindQuery::from_query_params(&params);
+    find_query.study_instance_uid = Some(study_uid);
+
+    match state.database.find_series(&find_query).await {
+        Ok(rows) => {
+            let body: Vec<_> = rows.iter().map(query::series_row_to_json).collect();
+            Json(body).into_response()
+        }
+        Err(e) => {
+            error!("QIDO-RS /series failed: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+/// QIDO-RS `GET /series/{uid}/instances`.
+async fn qido_instances(
+    State(state): State<WebState>,
+    Path(series_uid): Path<String>,
+) -> impl IntoResponse {
+    match state.database.find_instances(&series_uid).await {
+        Ok(rows) => {
+            let body: Vec<_> = rows.iter().map(query::instance_row_to_json).collect();
+            Json(body).into_response()
+        }
+        Err(e) => {
+            error!("QIDO-RS /instances failed: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+/// WADO-RS `GET /studies/{uid}/series/{s}/instances/{i}` - streams the
+/// stored `.dcm` bytes back as a single-part `multipart/related` message.
+async fn wado_instance(
+    State(state): State<WebState>,
+    Path((study_uid, series_uid, sop_uid)): Path<(String, String, String)>,
+) -> impl IntoResponse {
+    let location = match state
+        .database
+        .get_instance_location(&study_uid, &series_uid, &sop_uid)
+        .await
+    {
+        Ok(Some((location, _transfer_syntax))) => location,
+        Ok(None) => return StatusCode::NOT_FOUND.into_response(),
+        Err(e) => {
+            error!("WADO-RS lookup failed for {}: {}", sop_uid, e);
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+
+    match state.storage.retrieve(&location).await {
+        Ok(bytes) => {
+            let mut body = Vec::with_capacity(bytes.len() + 128);
+            body.extend_from_slice(
+                format!(
+                    "--{MULTIPART_BOUNDARY}\r\nContent-Type: application/dicom\r\n\r\n"
+                )
+                .as_bytes(),
+            );
+            body.extend_from_slice(&bytes);
+            body.extend_from_slice(format!("\r\n--{MULTIPART_BOUNDARY}--").as_bytes());
+
+            (
+                StatusCode::OK,
+                [(
+                    header::CONTENT_TYPE,
+                    format!(
+                        "multipart/related; type=\"application/dicom\"; boundary={MULTIPART_BOUNDARY}"
+                    ),
+                )],
+                body,
+            )
+                .into_response()
+        }
+        Err(e) => {
+            error!("WADO-RS retrieval failed for {}: {}", sop_uid, e);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+/// STOW-RS `POST /studies` - accepts a multipart body of DICOM parts and
+/// stores each one through the same pipeline as C-STORE.
+async fn stow_studies(State(state): State<WebState>, mut multipart: Multipart) -> impl IntoResponse {
+    let mut stored = 0usize;
+    let mut failed = 0usize;
+
+    loop {
+        let field = match multipart.next_field().await {
+            Ok(Some(field)) => field,
+            Ok(None) => break,
+            Err(e) => {
+                error!("STOW-RS failed to read multipart field: {}", e);
+                failed += 1;
+                continue;
+            }
+        };
+
+        match store_part(&state, field).await {
+            Ok(()) => stored += 1,
+            Err(e) => {
+                error!("STOW-RS failed to store a DICOM part: {}", e);
+                failed += 1;
+            }
+        }
+    }
+
+    let status = if stored == 0 && failed > 0 {
+        StatusCode::CONFLICT
+    } else {
+        StatusCode::OK
+    };
+    (status, Json(json!({ "stored": stored, "failed": failed }))).into_response()
+}
+
+async fn store_part(state: &WebState, field: axum::extract::multipart::Field<'_>) -> Result<()> {
+    let bytes: Bytes = field.bytes().await?;
+    let obj = dicom_object::from_reader(std::io::Cursor::new(bytes.as_ref()))?;
+    let obj = state.storage.maybe_transcode(obj);
+
+    let location = state.storage.store_dicom(&obj).await?;
+    let file_size = state.storage.get_size(&obj).await.unwrap_or(0) as i64;
+    state
+        .database
+        .store_dicom_metadata(&obj, &location.0, file_size)
+        .await?;
+    Ok(())
+}